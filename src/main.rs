@@ -14,7 +14,11 @@
 // "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
 // Licenses for the specific language governing permissions and limitations under the Licenses.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use clap::Parser;
 use git2::Repository;
@@ -85,6 +89,12 @@ impl std::convert::From<llm::error::LLMError> for CommitMessageError {
     }
 }
 
+const MODEL: &str = "gpt-4o";
+
+// Every generated message is committed as `wip: <message>`; validation has to account for that
+// prefix so `--max-subject-length` bounds what actually lands in the commit, not the raw message.
+const WIP_PREFIX: &str = "wip: ";
+
 const SYSTEM_PROMPT: &str = "You are an expert software engineer writing a git commit message.
 The user will provide a diff showing changes.
 Write a one-line commit message in the conventional style.
@@ -93,11 +103,44 @@ The message should:
 - Be under 72 characters
 ";
 
-async fn get_message(diff: String) -> Result<String, CommitMessageError> {
+#[derive(Clone)]
+struct GeneratedMessage {
+    message: String,
+    raw_arguments: String,
+}
+
+fn validate_conventional(message: &str, types: &[String], max_subject: usize) -> Result<(), String> {
+    if message.contains('\n') {
+        return Err("the message must be a single line".to_string());
+    }
+    let re = regex::Regex::new(r"^(?P<type>[a-z]+)(\([a-zA-Z0-9_.\-/ ]+\))?!?: .+$")
+        .expect("Regex failed to compile");
+    let caps = re
+        .captures(message)
+        .ok_or_else(|| "the message must have the form 'type(scope)?: subject'".to_string())?;
+    let commit_type = &caps["type"];
+    if !types.iter().any(|t| t == commit_type) {
+        return Err(format!(
+            "the type '{}' is not one of the allowed types: {}",
+            commit_type,
+            types.join(", ")
+        ));
+    }
+    let committed_length = WIP_PREFIX.chars().count() + message.chars().count();
+    if committed_length > max_subject {
+        return Err(format!(
+            "the committed subject \"{}{}\" would be {} characters long but must be at most {}",
+            WIP_PREFIX, message, committed_length, max_subject
+        ));
+    }
+    Ok(())
+}
+
+async fn get_message(diff: String, args: &Args) -> Result<GeneratedMessage, CommitMessageError> {
     debug!("Using system prompt: {}", &SYSTEM_PROMPT);
     let key = std::env::var("OPENAI_API_KEY").map_err(|_| CommitMessageError::MissingApiKey)?;
 
-    let messages = vec![ChatMessage {
+    let mut messages = vec![ChatMessage {
         role: ChatRole::User,
         message_type: MessageType::Text,
         content: format!("Diff:\n{}", diff),
@@ -105,7 +148,7 @@ async fn get_message(diff: String) -> Result<String, CommitMessageError> {
 
     let client = OpenAI::new(
         key,                             // api_key
-        Some("gpt-4o".to_string()),      // model
+        Some(MODEL.to_string()),         // model
         None,                            // max_tokens
         None,                            // temperature
         Some(60),                        // timeout_seconds
@@ -119,35 +162,242 @@ async fn get_message(diff: String) -> Result<String, CommitMessageError> {
         None,                            // reasoning_effort
     );
 
-    let response = client
-        .chat_with_tools(&messages, Some(&[commit_tool()]))
-        .await?;
-
-    // Extract the tool call from the response
-    let tool_calls = response
-        .tool_calls()
-        .ok_or(CommitMessageError::MissingToolCall)?;
-    let tool_call = tool_calls
-        .iter()
-        .find(|tc| tc.function.name == "write_commit_message")
-        .ok_or(CommitMessageError::MissingToolCall)?;
-
-    // Parse the arguments as JSON
-    let args: serde_json::Value = serde_json::from_str(&tool_call.function.arguments)
-        .map_err(|_| CommitMessageError::InvalidToolArguments)?;
-
-    let message = args
-        .get("message")
-        .and_then(|v| v.as_str())
-        .ok_or(CommitMessageError::InvalidToolArguments)?;
-
     // Filter out issue references and merge messages
     let issue_re =
         regex::Regex::new(r"(\(?(([Ff]ix(es)?)|([Cc]loses?))?\s*#\d+\)?)|([Mm]erge [Pp].*\n)")
             .expect("Regex failed to compile");
-    let commit_message = issue_re.replace_all(message, "");
 
-    Ok(commit_message.trim().to_string())
+    // Keep the last message we saw so we can fall back to it if every attempt fails validation.
+    let mut last: Option<GeneratedMessage> = None;
+    for attempt in 0..=args.max_retries {
+        let response = client
+            .chat_with_tools(&messages, Some(&[commit_tool()]))
+            .await?;
+
+        // Extract the tool call from the response
+        let tool_calls = response
+            .tool_calls()
+            .ok_or(CommitMessageError::MissingToolCall)?;
+        let tool_call = tool_calls
+            .iter()
+            .find(|tc| tc.function.name == "write_commit_message")
+            .ok_or(CommitMessageError::MissingToolCall)?;
+
+        // Parse the arguments as JSON
+        let raw_arguments = tool_call.function.arguments.clone();
+        let parsed: serde_json::Value = serde_json::from_str(&raw_arguments)
+            .map_err(|_| CommitMessageError::InvalidToolArguments)?;
+        let message = parsed
+            .get("message")
+            .and_then(|v| v.as_str())
+            .ok_or(CommitMessageError::InvalidToolArguments)?;
+        let commit_message = issue_re.replace_all(message, "").trim().to_string();
+
+        match validate_conventional(&commit_message, &args.commit_types, args.max_subject_length) {
+            Ok(()) => {
+                return Ok(GeneratedMessage {
+                    message: commit_message,
+                    raw_arguments,
+                });
+            }
+            Err(reason) => {
+                debug!(
+                    "Generated message failed validation on attempt {}: {}",
+                    attempt, reason
+                );
+                messages.push(ChatMessage {
+                    role: ChatRole::User,
+                    message_type: MessageType::Text,
+                    content: format!(
+                        "The commit message \"{}\" is invalid: {}. \
+                         Call write_commit_message again with a corrected message.",
+                        commit_message, reason
+                    ),
+                });
+                last = Some(GeneratedMessage {
+                    message: commit_message,
+                    raw_arguments,
+                });
+            }
+        }
+    }
+
+    // Retry budget exhausted: return a best-effort, truncated message so commits never stall.
+    let mut fallback = last.ok_or(CommitMessageError::MissingToolCall)?;
+    let max_message_length = args
+        .max_subject_length
+        .saturating_sub(WIP_PREFIX.chars().count());
+    if fallback.message.chars().count() > max_message_length {
+        fallback.message = fallback
+            .message
+            .chars()
+            .take(max_message_length)
+            .collect();
+    }
+    debug!("Falling back to best-effort message: {}", fallback.message);
+    Ok(fallback)
+}
+
+// Keyed on the diff plus every arg that affects what validate_conventional will accept, so a
+// message cached under one set of flags is never served back under a stricter set.
+//
+// Landing prerequisite: this is the only use of the `sha2` crate in the codebase, and it must be
+// added to `[dependencies]` before this builds -- unlike git2/regex/clap/etc., the baseline never
+// pulled it in.
+fn cache_key(diff: &str, args: &Args) -> String {
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    hasher.update(diff.as_bytes());
+    hasher.update(0u8.to_le_bytes());
+    hasher.update(args.commit_types.join(",").as_bytes());
+    hasher.update(0u8.to_le_bytes());
+    hasher.update(args.max_subject_length.to_le_bytes());
+    hasher.update(0u8.to_le_bytes());
+    hasher.update(args.max_retries.to_le_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+struct CacheEntry {
+    inserted: Instant,
+    message: GeneratedMessage,
+}
+
+struct MessageCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: HashMap<String, CacheEntry>,
+    order: VecDeque<String>,
+}
+
+impl MessageCache {
+    fn new(ttl: Duration, capacity: usize) -> Self {
+        MessageCache {
+            ttl,
+            capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn get(&mut self, digest: &str) -> Option<GeneratedMessage> {
+        let expired = self
+            .entries
+            .get(digest)
+            .map(|e| e.inserted.elapsed() > self.ttl)?;
+        if expired {
+            self.entries.remove(digest);
+            self.order.retain(|d| d != digest);
+            None
+        } else {
+            self.entries.get(digest).map(|e| e.message.clone())
+        }
+    }
+
+    fn insert(&mut self, digest: String, message: GeneratedMessage) {
+        if self.capacity == 0 {
+            return;
+        }
+        if !self.entries.contains_key(&digest) {
+            self.order.push_back(digest.clone());
+        }
+        self.entries.insert(
+            digest,
+            CacheEntry {
+                inserted: Instant::now(),
+                message,
+            },
+        );
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    // Loaded entries are treated as freshly inserted, so the TTL runs from process start.
+    fn load(&mut self, path: &std::path::Path) {
+        let raw = match std::fs::read_to_string(path) {
+            Ok(raw) => raw,
+            Err(_) => return,
+        };
+        let Ok(value) = serde_json::from_str::<serde_json::Value>(&raw) else {
+            return;
+        };
+        let Some(map) = value.as_object() else {
+            return;
+        };
+        for (digest, entry) in map {
+            let message = entry.get("message").and_then(|v| v.as_str());
+            let raw_arguments = entry.get("raw_arguments").and_then(|v| v.as_str());
+            if let (Some(message), Some(raw_arguments)) = (message, raw_arguments) {
+                self.insert(
+                    digest.clone(),
+                    GeneratedMessage {
+                        message: message.to_string(),
+                        raw_arguments: raw_arguments.to_string(),
+                    },
+                );
+            }
+        }
+    }
+
+    fn persist(&self, path: &std::path::Path) {
+        let map: serde_json::Map<String, serde_json::Value> = self
+            .entries
+            .iter()
+            .map(|(digest, entry)| {
+                (
+                    digest.clone(),
+                    serde_json::json!({
+                        "message": entry.message.message,
+                        "raw_arguments": entry.message.raw_arguments,
+                    }),
+                )
+            })
+            .collect();
+        if let Ok(serialized) = serde_json::to_string(&serde_json::Value::Object(map)) {
+            if let Err(e) = std::fs::write(path, serialized) {
+                debug!("Could not persist gwipt cache: {}", e);
+            }
+        }
+    }
+}
+
+static MESSAGE_CACHE: OnceLock<Mutex<MessageCache>> = OnceLock::new();
+
+async fn get_message_cached(
+    diff: String,
+    args: &Args,
+    cache_path: &std::path::Path,
+) -> Result<GeneratedMessage, CommitMessageError> {
+    let digest = cache_key(&diff, args);
+    let cache = MESSAGE_CACHE.get_or_init(|| {
+        // `Duration::from_secs_f64` panics on a negative, NaN, or infinite value, and
+        // `--cache-ttl` comes straight from the command line, so clamp it first.
+        let ttl_secs = if args.cache_ttl.is_finite() {
+            args.cache_ttl.max(0.0)
+        } else {
+            0.0
+        };
+        let mut cache = MessageCache::new(Duration::from_secs_f64(ttl_secs), args.cache_capacity);
+        if args.cache_persist {
+            cache.load(cache_path);
+        }
+        Mutex::new(cache)
+    });
+
+    if let Some(hit) = cache.lock().unwrap().get(&digest) {
+        debug!("Reusing cached commit message for identical diff");
+        return Ok(hit);
+    }
+
+    let message = get_message(diff, args).await?;
+    let mut guard = cache.lock().unwrap();
+    guard.insert(digest, message.clone());
+    if args.cache_persist {
+        guard.persist(cache_path);
+    }
+    Ok(message)
 }
 
 fn prepare_wip_branch(repo: &Repository) -> Result<String, git2::Error> {
@@ -206,16 +456,65 @@ fn prepare_diff<'a>(
     Ok(diff)
 }
 
+// Turn an `--exclude` glob into an anchored regex: `*` matches any run of characters (including
+// `/`), `?` matches any one character, and every other character (brackets too) matches literally.
+fn glob_to_regex(glob: &str) -> regex::Regex {
+    let mut pattern = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            '.' | '+' | '(' | ')' | '|' | '^' | '$' | '[' | ']' | '{' | '}' | '\\' => {
+                pattern.push('\\');
+                pattern.push(c);
+            }
+            _ => pattern.push(c),
+        }
+    }
+    pattern.push('$');
+    regex::Regex::new(&pattern).expect("glob pattern produced an invalid regex")
+}
+
+// Excludes are matched in our own code rather than via libgit2's `:(exclude)` pathspec magic:
+// that magic's support is partial and version-dependent, so it can silently fail to exclude
+// anything on a libgit2 build that doesn't implement it.
+fn is_excluded(path: &str, excludes: &[String]) -> bool {
+    excludes.iter().any(|glob| glob_to_regex(glob).is_match(path))
+}
+
+fn has_trackable_changes(repo: &Repository, args: &Args) -> Result<bool, git2::Error> {
+    let mut options = git2::StatusOptions::new();
+    options
+        .include_ignored(false)
+        .include_untracked(true)
+        .recurse_untracked_dirs(true);
+    let statuses = repo.statuses(Some(&mut options))?;
+    Ok(statuses
+        .iter()
+        .any(|entry| !entry.path().is_some_and(|p| is_excluded(p, &args.exclude))))
+}
+
 fn try_commit(
     repo: &Repository,
+    args: &Args,
     wip_branch_name: &str,
     commit_message: &str,
 ) -> Result<git2::Oid, git2::Error> {
     // at this point, we have a wip branch ready to go. We need to add everything (other than
-    // ignored stuff) in the current working directory to a tree, and commit it to the tip of the
-    // wip branch.
+    // ignored and explicitly excluded stuff) in the current working directory to a tree, and
+    // commit it to the tip of the wip branch.
     let mut index = repo.index()?;
     index.add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)?;
+    let excluded_paths: Vec<std::path::PathBuf> = index
+        .iter()
+        .filter_map(|entry| {
+            let path = std::str::from_utf8(&entry.path).ok()?;
+            is_excluded(path, &args.exclude).then(|| std::path::PathBuf::from(path))
+        })
+        .collect();
+    for path in excluded_paths {
+        index.remove_path(&path)?;
+    }
     let branch = repo.find_branch(wip_branch_name, git2::BranchType::Local)?;
     let result_tree_id = index.write_tree()?;
     let result_tree = repo.find_tree(result_tree_id)?;
@@ -251,6 +550,360 @@ fn diff_lines(diff: &git2::Diff) -> Result<Vec<String>, git2::Error> {
     Ok(lines)
 }
 
+const NOTES_REF: &str = "refs/notes/gwipt";
+
+fn write_note(
+    repo: &Repository,
+    oid: git2::Oid,
+    diff: &str,
+    raw_arguments: &str,
+) -> Result<(), git2::Error> {
+    let me = repo.signature()?;
+    let content = format!(
+        "model: {model}\n\n\
+         === system prompt ===\n{system}\n\
+         === tool-call arguments ===\n{args}\n\
+         === diff ===\n{diff}",
+        model = MODEL,
+        system = SYSTEM_PROMPT,
+        args = raw_arguments,
+        diff = diff,
+    );
+    repo.note(&me, &me, Some(NOTES_REF), oid, &content, false)?;
+    Ok(())
+}
+
+fn notify_push(
+    repo: &Repository,
+    remote_name: &str,
+    wip_branch_name: &str,
+) -> Result<(), git2::Error> {
+    let mut remote = repo.find_remote(remote_name)?;
+    let mut callbacks = git2::RemoteCallbacks::new();
+    callbacks.credentials(|url, username_from_url, _allowed| {
+        let config = git2::Config::open_default()?;
+        git2::Cred::credential_helper(&config, url, username_from_url)
+    });
+    let mut push_options = git2::PushOptions::new();
+    push_options.remote_callbacks(callbacks);
+    // Force-pushed: maybe_retain rewrites this branch's history (squash/re-parent), so a plain
+    // fast-forward refspec would reject every push once retention has run once.
+    let refspec = format!("+refs/heads/{0}:refs/heads/{0}", wip_branch_name);
+    remote.push(&[refspec.as_str()], Some(&mut push_options))?;
+    Ok(())
+}
+
+fn notify_email(
+    sendmail: &str,
+    recipients: &[String],
+    from: &git2::Signature,
+    commit_message: &str,
+    patch: &str,
+) -> Result<(), std::io::Error> {
+    let subject = commit_message.lines().next().unwrap_or(commit_message);
+    let from_header = format!(
+        "{} <{}>",
+        from.name().unwrap_or("gwipt"),
+        from.email().unwrap_or("gwipt@localhost")
+    );
+    let mut message = String::new();
+    message.push_str(&format!("From: {}\n", from_header));
+    for recipient in recipients {
+        message.push_str(&format!("To: {}\n", recipient));
+    }
+    message.push_str(&format!("Subject: {}\n", subject));
+    message.push_str("Content-Type: text/plain; charset=utf-8\n\n");
+    message.push_str(patch);
+
+    let mut child = Command::new(sendmail)
+        .arg("-t")
+        .stdin(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .expect("sendmail child was spawned with a piped stdin")
+        .write_all(message.as_bytes())?;
+    child.wait()?;
+    Ok(())
+}
+
+fn notify(repo: &Repository, args: &Args, wip_branch_name: &str, commit_message: &str, patch: &str) {
+    let channels: Vec<&str> = args
+        .notify
+        .as_deref()
+        .map(|s| s.split(',').map(str::trim).filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+    if channels.contains(&"push") {
+        if let Err(e) = notify_push(repo, &args.remote, wip_branch_name) {
+            error!("Failed to push {} to {}: {}", wip_branch_name, args.remote, e);
+        }
+    }
+    if channels.contains(&"email") {
+        if args.recipient.is_empty() {
+            error!("--notify=email requested but no --recipient was given");
+        } else {
+            match repo.signature() {
+                Ok(from) => {
+                    if let Err(e) =
+                        notify_email(&args.sendmail, &args.recipient, &from, commit_message, patch)
+                    {
+                        error!("Failed to send commit email: {}", e);
+                    }
+                }
+                Err(e) => error!("Could not build a From address for commit email: {}", e),
+            }
+        }
+    }
+}
+
+async fn summarize_wip(messages: &[String]) -> String {
+    let fallback = messages
+        .iter()
+        .map(|m| format!("- {}", m))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let key = match std::env::var("OPENAI_API_KEY") {
+        Ok(key) => key,
+        Err(_) => return fallback,
+    };
+    let client = OpenAI::new(
+        key,
+        Some(MODEL.to_string()),
+        None,
+        None,
+        Some(60),
+        Some(
+            "You condense a list of work-in-progress commit messages into a concise bulleted \
+             changelog. Respond with bullet points only."
+                .to_string(),
+        ),
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+    );
+    let chat_messages = vec![ChatMessage {
+        role: ChatRole::User,
+        message_type: MessageType::Text,
+        content: format!("Summarize these wip commits:\n{}", fallback),
+    }];
+    match client.chat(&chat_messages).await {
+        Ok(response) => response
+            .text()
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .unwrap_or(fallback),
+        Err(e) => {
+            debug!("Roll-up summarization failed, using literal bullets: {}", e);
+            fallback
+        }
+    }
+}
+
+// A rollup commit's message is "wip: roll up N commits\n\n<body>"; this returns just the body.
+fn rollup_body(commit: &git2::Commit) -> String {
+    commit
+        .message()
+        .and_then(|m| m.splitn(2, "\n\n").nth(1))
+        .unwrap_or("")
+        .to_string()
+}
+
+// A rollup commit's summary is "wip: roll up N commits"; this recovers N.
+fn rollup_count(commit: &git2::Commit) -> usize {
+    commit
+        .summary()
+        .and_then(|s| s.strip_prefix("wip: roll up "))
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0)
+}
+
+// The pre-squash tip stays reachable only through the branch reflog that `Repository::reference`
+// writes when it moves the ref, so squashed commits' notes are folded onto the rollup below.
+async fn maybe_retain(
+    repo: &Repository,
+    args: &Args,
+    wip_branch_name: &str,
+) -> Result<(), git2::Error> {
+    if args.retain_max_commits.is_none() && args.retain_max_age.is_none() {
+        return Ok(());
+    }
+
+    let branch = repo.find_branch(wip_branch_name, git2::BranchType::Local)?;
+    let tip = branch.get().peel_to_commit()?;
+
+    // Walk the first-parent chain down to the first commit that gwipt did not create, or to a
+    // prior rollup, whichever comes first. A rollup's summary also starts with "wip:", but it is
+    // already squashed and must not be treated as a fresh candidate, or the branch would oscillate
+    // (re-qualifying for, and re-paying for, a rollup on every subsequent commit).
+    let mut chain = Vec::new();
+    let mut current = tip.clone();
+    let last_retained = loop {
+        let summary = current.summary().unwrap_or("");
+        let is_rollup = summary.starts_with("wip: roll up ");
+        let is_wip =
+            !is_rollup && (summary.starts_with("wip:") || summary == "Merge HEAD into wip/ branch");
+        if !is_wip {
+            break current;
+        }
+        chain.push(current.clone());
+        match current.parent(0) {
+            Ok(parent) => current = parent,
+            Err(_) => break current,
+        }
+    };
+
+    let count = chain.len();
+    if count < 2 {
+        return Ok(());
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let oldest_age = chain
+        .last()
+        .map(|c| now - c.time().seconds())
+        .unwrap_or(0);
+    let over_count = args.retain_max_commits.is_some_and(|m| count > m);
+    let over_age = args.retain_max_age.is_some_and(|a| oldest_age > a as i64);
+    if !over_count && !over_age {
+        return Ok(());
+    }
+
+    // `chain` is newest-first (chain[0] is the tip). Keep the longest prefix that satisfies both
+    // configured thresholds and squash only the older remainder, so commits inside the threshold
+    // survive as themselves instead of being folded into the rollup.
+    let keep_by_count = args.retain_max_commits.map_or(count, |m| count.min(m));
+    let keep_by_age = args.retain_max_age.map_or(count, |a| {
+        chain
+            .iter()
+            .take_while(|c| now - c.time().seconds() <= a as i64)
+            .count()
+    });
+    let keep_count = keep_by_count.min(keep_by_age);
+    let squashed = &chain[keep_count..];
+    if squashed.is_empty() {
+        return Ok(());
+    }
+
+    // If the commit right below the retained/squashable chain is itself a rollup, fold the newly
+    // squashed commits into it instead of stacking a new rollup on top of it -- otherwise the
+    // branch never actually shrinks, it just keeps relabeling a linear chain of "roll up 1
+    // commits" rollups forever.
+    let prior_rollup = (last_retained.summary().unwrap_or("")).starts_with("wip: roll up ");
+    let squash_parent = if prior_rollup {
+        last_retained.parent(0)?
+    } else {
+        last_retained.clone()
+    };
+    let prior_count = if prior_rollup { rollup_count(&last_retained) } else { 0 };
+    let total_count = prior_count + squashed.len();
+
+    // Oldest-first list of the newly squashed commits' messages.
+    let messages: Vec<String> = squashed
+        .iter()
+        .rev()
+        .map(|c| c.summary().unwrap_or("").to_string())
+        .collect();
+
+    // Folding in a single stale commit under steady one-at-a-time editing is the common case once
+    // a rollup exists; that's not "a genuinely new squash range" worth a paid summarization
+    // round-trip, so just append it as a bullet instead of re-summarizing everything.
+    let body = if prior_rollup && squashed.len() == 1 {
+        let mut body = rollup_body(&last_retained);
+        if !body.is_empty() {
+            body.push('\n');
+        }
+        body.push_str(&format!("- {}", messages[0]));
+        body
+    } else {
+        let mut to_summarize = Vec::new();
+        if prior_rollup {
+            to_summarize.push(rollup_body(&last_retained));
+        }
+        to_summarize.extend(messages);
+        summarize_wip(&to_summarize).await
+    };
+    let rollup_message = format!("wip: roll up {} commits\n\n{}", total_count, body);
+    let rollup_tree = squashed[0].tree()?;
+    let me = repo.signature()?;
+    let rollup_id = repo.commit(
+        None,
+        &me,
+        &me,
+        &rollup_message,
+        &rollup_tree,
+        &[&squash_parent],
+    )?;
+
+    // Fold the squashed commits' notes onto the rollup so the audit trail survives reflog expiry,
+    // carrying forward the prior rollup's own combined note too since its commit is being replaced.
+    let mut combined_notes = String::new();
+    if prior_rollup {
+        if let Ok(note) = repo.find_note(Some(NOTES_REF), last_retained.id()) {
+            if let Some(message) = note.message() {
+                combined_notes.push_str(message);
+            }
+        }
+    }
+    for commit in squashed.iter().rev() {
+        if let Ok(note) = repo.find_note(Some(NOTES_REF), commit.id()) {
+            if let Some(message) = note.message() {
+                combined_notes.push_str(&format!("=== note for {} ===\n{}\n\n", commit.id(), message));
+            }
+        }
+    }
+    if !combined_notes.is_empty() {
+        if let Err(e) = repo.note(&me, &me, Some(NOTES_REF), rollup_id, &combined_notes, false) {
+            error!("Failed to carry notes forward onto rollup {}: {}", rollup_id, e);
+        }
+    }
+
+    // Re-parent the retained commits onto the rollup, oldest-first; rewriting a commit changes its
+    // oid, so carry each one's note forward to the new id as we go.
+    let mut parent_id = rollup_id;
+    for commit in chain[..keep_count].iter().rev() {
+        let parent = repo.find_commit(parent_id)?;
+        let new_id = repo.commit(
+            None,
+            &commit.author(),
+            &commit.committer(),
+            commit.message().unwrap_or(""),
+            &commit.tree()?,
+            &[&parent],
+        )?;
+        if let Ok(note) = repo.find_note(Some(NOTES_REF), commit.id()) {
+            if let Some(message) = note.message() {
+                if let Err(e) = repo.note(&me, &me, Some(NOTES_REF), new_id, message, false) {
+                    error!("Failed to carry note forward onto rewritten commit {}: {}", new_id, e);
+                }
+            }
+        }
+        parent_id = new_id;
+    }
+    repo.reference(
+        &(String::from("refs/heads/") + wip_branch_name),
+        parent_id,
+        true,
+        "gwipt: retain/squash wip branch",
+    )?;
+
+    info!(
+        "Folded {} more wip commit(s) into rollup {} ({} total), retaining {} newer commits",
+        squashed.len(),
+        &rollup_id.to_string()[..6],
+        total_count,
+        keep_count
+    );
+    Ok(())
+}
+
 #[derive(Debug)]
 enum ChangeHandlingError {
     Git(git2::Error),
@@ -290,7 +943,7 @@ impl std::convert::From<std::str::Utf8Error> for ChangeHandlingError {
     }
 }
 
-async fn handle_change_inner(repo: &Repository) -> Result<(), ChangeHandlingError> {
+async fn handle_change_inner(repo: &Repository, args: &Args) -> Result<(), ChangeHandlingError> {
     let name = prepare_wip_branch(repo)?;
     let diff = prepare_diff(repo, &name)?;
     let lines = diff_lines(&diff)?;
@@ -299,16 +952,29 @@ async fn handle_change_inner(repo: &Repository) -> Result<(), ChangeHandlingErro
         return Ok(());
     }
     let text = lines.join("");
-    let message = get_message(text).await?;
+    let cache_path = repo.path().join("gwipt-cache");
+    let generated = get_message_cached(text.clone(), args, &cache_path).await?;
     debug!("Got a commit message");
-    let id = try_commit(repo, &name, &(String::from("wip: ") + &message))?;
-    info!("Commit {}: {}", &id.to_string()[..6], message);
+    let full_message = String::from(WIP_PREFIX) + &generated.message;
+    let id = try_commit(repo, args, &name, &full_message)?;
+    info!("Commit {}: {}", &id.to_string()[..6], generated.message);
+    if !args.no_notes {
+        if let Err(e) = write_note(repo, id, &text, &generated.raw_arguments) {
+            error!("Failed to write gwipt note for {}: {}", id, e);
+        }
+    }
+    // Retention rewrites the wip branch, so it must run before notify: pushing first would ship a
+    // tip that's about to be rewritten out from under the remote.
+    if let Err(e) = maybe_retain(repo, args, &name).await {
+        error!("Retention policy failed for {}: {}", name, e);
+    }
+    notify(repo, args, &name, &full_message, &text);
     Ok(())
 }
 
-async fn handle_change() {
+async fn handle_change(args: &Args) {
     let repo = Repository::discover(".").unwrap();
-    handle_change_inner(&repo)
+    handle_change_inner(&repo, args)
         .await
         .unwrap_or_else(|e| error!("{}", e))
 }
@@ -351,12 +1017,72 @@ impl std::convert::From<time::error::IndeterminateOffset> for AppError {
 }
 
 /// Automatic work-in-progress commits with descriptive commit messages generated by GPT-3 Codex
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// How long to wait to accumulate changes before committing, in secs. Recommended to be >= 0.1
     #[arg(short, long, default_value_t = 0.1)]
     time_delay: f64,
+
+    /// Comma-separated list of post-commit notifications to run: `push`, `email`, or both
+    #[arg(long)]
+    notify: Option<String>,
+
+    /// Remote to push the wip/ branch to when `--notify` includes `push`
+    #[arg(long, default_value = "origin")]
+    remote: String,
+
+    /// Recipient address for commit emails; repeat for multiple recipients
+    #[arg(long)]
+    recipient: Vec<String>,
+
+    /// Path to a sendmail-compatible MTA used when `--notify` includes `email`
+    #[arg(long, default_value = "sendmail")]
+    sendmail: String,
+
+    /// Do not record an audit note under refs/notes/gwipt for each generated commit
+    #[arg(long)]
+    no_notes: bool,
+
+    /// How long, in secs, a cached message for a given diff stays valid
+    #[arg(long, default_value_t = 300.0)]
+    cache_ttl: f64,
+
+    /// Maximum number of diff digests to keep in the in-process response cache
+    #[arg(long, default_value_t = 128)]
+    cache_capacity: usize,
+
+    /// Persist the response cache to .git/gwipt-cache so restarts keep the most recently inserted entries
+    #[arg(long)]
+    cache_persist: bool,
+
+    /// Allowed conventional-commit types the generated message must start with
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "feat,fix,docs,style,refactor,perf,test,build,ci,chore,revert"
+    )]
+    commit_types: Vec<String>,
+
+    /// Maximum length, in characters, of the committed subject line, including the `wip: ` prefix
+    #[arg(long, default_value_t = 72)]
+    max_subject_length: usize,
+
+    /// How many times to re-ask the model when its message fails validation
+    #[arg(long, default_value_t = 2)]
+    max_retries: usize,
+
+    /// Squash wip commits once more than this many have accumulated on the branch
+    #[arg(long)]
+    retain_max_commits: Option<usize>,
+
+    /// Squash wip commits once the oldest is older than this many secs
+    #[arg(long)]
+    retain_max_age: Option<f64>,
+
+    /// Glob of paths to exclude from status checks and staging; repeat for multiple globs
+    #[arg(long)]
+    exclude: Vec<String>,
 }
 
 fn main() -> Result<(), AppError> {
@@ -386,25 +1112,25 @@ fn main() -> Result<(), AppError> {
 
     debug!("Doing an unconditional first pass in case there are existing changes to commit.");
     let rt = tokio::runtime::Runtime::new().unwrap();
-    rt.block_on(handle_change());
+    rt.block_on(handle_change(&args));
 
+    let notify_args = args.clone();
     let mut debouncer = new_debouncer(
         std::time::Duration::from_secs_f64(args.time_delay),
         None,
         move |res: DebounceEventResult| match res {
             Ok(events) => {
                 debug!("{} file events", events.len());
-                let any_non_git_files = events.iter().any(|e| {
-                    let p = &e.path;
-                    !p.components().any(|part| {
-                        part == std::path::Component::Normal(std::ffi::OsStr::new(".git"))
-                    })
-                });
-                if any_non_git_files {
-                    debug!("Found files not in a .git directory");
-                    rt.block_on(handle_change());
-                } else {
-                    debug!("No files outside of .git changed");
+                match Repository::discover(".") {
+                    Ok(repo) => match has_trackable_changes(&repo, &notify_args) {
+                        Ok(true) => {
+                            debug!("Found trackable changes");
+                            rt.block_on(handle_change(&notify_args));
+                        }
+                        Ok(false) => debug!("No trackable changes"),
+                        Err(e) => error!("Error checking repository status: {}", e),
+                    },
+                    Err(e) => error!("Could not open repository: {}", e),
                 }
             }
             Err(e) => error!("Error watching files: {:?}", e),
@@ -418,3 +1144,255 @@ fn main() -> Result<(), AppError> {
         std::thread::sleep(std::time::Duration::from_secs(10));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn unique_temp_dir(tag: &str) -> std::path::PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+        std::env::temp_dir().join(format!("gwipt-test-{}-{}-{}", std::process::id(), tag, n))
+    }
+
+    // A repo with one baseline commit on `main` and a `wip/main` branch ready for test commits.
+    fn init_test_repo(tag: &str) -> (std::path::PathBuf, Repository, String) {
+        let dir = unique_temp_dir(tag);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut opts = git2::RepositoryInitOptions::new();
+        opts.initial_head("main");
+        let repo = Repository::init_opts(&dir, &opts).unwrap();
+        {
+            let mut config = repo.config().unwrap();
+            config.set_str("user.name", "gwipt-test").unwrap();
+            config.set_str("user.email", "gwipt-test@example.com").unwrap();
+        }
+        std::fs::write(dir.join("README"), "baseline\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(std::path::Path::new("README")).unwrap();
+        let tree_id = index.write_tree().unwrap();
+        let tree = repo.find_tree(tree_id).unwrap();
+        let sig = repo.signature().unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "baseline", &tree, &[])
+            .unwrap();
+        let wip_branch_name = prepare_wip_branch(&repo).unwrap();
+        (dir, repo, wip_branch_name)
+    }
+
+    // Appends a wip commit reusing its parent's tree (these tests only care about the commit
+    // graph, not file contents), backdated by `seconds_ago` so retention's age math is testable.
+    fn commit_wip(
+        repo: &Repository,
+        branch_ref: &str,
+        parent: &git2::Commit,
+        message: &str,
+        seconds_ago: i64,
+    ) -> git2::Oid {
+        let base = repo.signature().unwrap();
+        let when = git2::Time::new(
+            base.when().seconds() - seconds_ago,
+            base.when().offset_minutes(),
+        );
+        let sig = git2::Signature::new(base.name().unwrap(), base.email().unwrap(), &when).unwrap();
+        let tree = parent.tree().unwrap();
+        repo.commit(Some(branch_ref), &sig, &sig, message, &tree, &[parent])
+            .unwrap()
+    }
+
+    fn branch_tip(repo: &Repository, name: &str) -> git2::Commit {
+        repo.find_branch(name, git2::BranchType::Local)
+            .unwrap()
+            .get()
+            .peel_to_commit()
+            .unwrap()
+    }
+
+    fn first_parent_chain(tip: &git2::Commit) -> Vec<String> {
+        let mut summaries = Vec::new();
+        let mut current = tip.clone();
+        loop {
+            summaries.push(current.summary().unwrap_or("").to_string());
+            match current.parent(0) {
+                Ok(parent) => current = parent,
+                Err(_) => break,
+            }
+        }
+        summaries
+    }
+
+    fn run_retain(repo: &Repository, args: &Args, wip_branch_name: &str) {
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(maybe_retain(repo, args, wip_branch_name))
+            .unwrap();
+    }
+
+    #[test]
+    fn validate_conventional_accepts_previously_flagged_imperatives() {
+        // "embed"/"bring" end in "ed"/"ing" but are legitimate imperatives; the old
+        // suffix-guessing heuristic rejected them.
+        assert!(validate_conventional("fix: embed the schema version", &["fix".to_string()], 72).is_ok());
+        assert!(validate_conventional("feat: bring the parser up to date", &["feat".to_string()], 72).is_ok());
+    }
+
+    #[test]
+    fn validate_conventional_rejects_bad_shape() {
+        let types = vec!["fix".to_string(), "feat".to_string()];
+        assert!(validate_conventional("fix: a\nb", &types, 72).is_err());
+        assert!(validate_conventional("weird: do a thing", &types, 72).is_err());
+        assert!(validate_conventional("not conventional at all", &types, 72).is_err());
+    }
+
+    #[test]
+    fn validate_conventional_bounds_the_committed_subject_not_the_raw_message() {
+        let types = vec!["fix".to_string()];
+        // "wip: " is 5 chars; a 10-char message plus that prefix is 15, over a max of 10.
+        assert!(validate_conventional("fix: abcde", &types, 10).is_err());
+        assert!(validate_conventional("fix: abcde", &types, 15).is_ok());
+    }
+
+    #[test]
+    fn cache_key_changes_with_validation_affecting_args() {
+        let diff = "diff --git a/x b/x\n+hello\n";
+        let strict = Args::parse_from(["gwipt", "--max-subject-length", "40"]);
+        let loose = Args::parse_from(["gwipt", "--max-subject-length", "72"]);
+        assert_ne!(cache_key(diff, &strict), cache_key(diff, &loose));
+        assert_eq!(cache_key(diff, &strict), cache_key(diff, &strict));
+    }
+
+    #[test]
+    fn message_cache_expires_by_ttl_and_evicts_by_capacity() {
+        let message = GeneratedMessage {
+            message: "fix: something".to_string(),
+            raw_arguments: "{}".to_string(),
+        };
+        let mut cache = MessageCache::new(Duration::from_millis(1), 128);
+        cache.insert("a".to_string(), message.clone());
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(cache.get("a").is_none());
+
+        let mut cache = MessageCache::new(Duration::from_secs(300), 2);
+        cache.insert("a".to_string(), message.clone());
+        cache.insert("b".to_string(), message.clone());
+        cache.insert("c".to_string(), message.clone());
+        assert!(cache.get("a").is_none(), "oldest entry should be evicted");
+        assert!(cache.get("b").is_some());
+        assert!(cache.get("c").is_some());
+    }
+
+    #[test]
+    fn is_excluded_matches_shell_style_globs() {
+        let excludes = vec!["*.log".to_string()];
+        assert!(is_excluded("debug.log", &excludes));
+        assert!(!is_excluded("debug.txt", &excludes));
+    }
+
+    #[test]
+    fn try_commit_does_not_stage_excluded_paths() {
+        let (dir, repo, wip_branch_name) = init_test_repo("try-commit");
+        std::fs::write(dir.join("keep.txt"), "keep\n").unwrap();
+        std::fs::write(dir.join("skip.log"), "skip\n").unwrap();
+        let mut args = Args::parse_from(["gwipt"]);
+        args.exclude = vec!["*.log".to_string()];
+
+        let id = try_commit(&repo, &args, &wip_branch_name, "wip: add files").unwrap();
+        let tree = repo.find_commit(id).unwrap().tree().unwrap();
+        assert!(tree.get_name("keep.txt").is_some());
+        assert!(tree.get_name("skip.log").is_none());
+    }
+
+    #[test]
+    fn maybe_retain_leaves_the_branch_alone_under_the_threshold() {
+        let (_dir, repo, wip_branch_name) = init_test_repo("retain-under");
+        let branch_ref = format!("refs/heads/{}", wip_branch_name);
+        let mut parent = branch_tip(&repo, &wip_branch_name);
+        for i in 0..3 {
+            commit_wip(&repo, &branch_ref, &parent, &format!("wip: change {}", i), 0);
+            parent = branch_tip(&repo, &wip_branch_name);
+        }
+        let before = parent.id();
+
+        let mut args = Args::parse_from(["gwipt"]);
+        args.retain_max_commits = Some(3);
+        run_retain(&repo, &args, &wip_branch_name);
+
+        let after = branch_tip(&repo, &wip_branch_name);
+        assert_eq!(before, after.id(), "nothing should be rewritten at the threshold");
+    }
+
+    #[test]
+    fn maybe_retain_squashes_only_the_overage_and_keeps_the_rest() {
+        let (_dir, repo, wip_branch_name) = init_test_repo("retain-over");
+        let branch_ref = format!("refs/heads/{}", wip_branch_name);
+        let mut tip = branch_tip(&repo, &wip_branch_name);
+        for i in 0..4 {
+            commit_wip(&repo, &branch_ref, &tip, &format!("wip: change {}", i), 0);
+            tip = branch_tip(&repo, &wip_branch_name);
+        }
+
+        let mut args = Args::parse_from(["gwipt"]);
+        args.retain_max_commits = Some(3);
+        run_retain(&repo, &args, &wip_branch_name);
+
+        let tip = branch_tip(&repo, &wip_branch_name);
+        let chain = first_parent_chain(&tip);
+        // 3 retained commits, then exactly one rollup, then the baseline.
+        assert_eq!(chain[0], "wip: change 3");
+        assert_eq!(chain[1], "wip: change 2");
+        assert_eq!(chain[2], "wip: change 1");
+        assert_eq!(chain[3], "wip: roll up 1 commits");
+        assert_eq!(chain[4], "baseline");
+    }
+
+    #[test]
+    fn maybe_retain_merges_into_the_existing_rollup_instead_of_stacking() {
+        let (_dir, repo, wip_branch_name) = init_test_repo("retain-merge");
+        let branch_ref = format!("refs/heads/{}", wip_branch_name);
+        let mut tip = branch_tip(&repo, &wip_branch_name);
+        for i in 0..4 {
+            commit_wip(&repo, &branch_ref, &tip, &format!("wip: change {}", i), 0);
+            tip = branch_tip(&repo, &wip_branch_name);
+        }
+        let mut args = Args::parse_from(["gwipt"]);
+        args.retain_max_commits = Some(3);
+        run_retain(&repo, &args, &wip_branch_name);
+
+        // One more commit pushes the retained set over the threshold again.
+        let tip = branch_tip(&repo, &wip_branch_name);
+        commit_wip(&repo, &branch_ref, &tip, "wip: change 4", 0);
+        run_retain(&repo, &args, &wip_branch_name);
+
+        let tip = branch_tip(&repo, &wip_branch_name);
+        let chain = first_parent_chain(&tip);
+        let rollups: Vec<&String> = chain.iter().filter(|s| s.starts_with("wip: roll up ")).collect();
+        assert_eq!(
+            rollups.len(),
+            1,
+            "a second squash must fold into the existing rollup, not stack a new one: {:?}",
+            chain
+        );
+        assert_eq!(rollups[0], "wip: roll up 2 commits");
+        assert_eq!(chain.last().unwrap(), "baseline");
+    }
+
+    #[test]
+    fn maybe_retain_squashes_by_age() {
+        let (_dir, repo, wip_branch_name) = init_test_repo("retain-age");
+        let branch_ref = format!("refs/heads/{}", wip_branch_name);
+        let mut tip = branch_tip(&repo, &wip_branch_name);
+        commit_wip(&repo, &branch_ref, &tip, "wip: old change", 1_000);
+        tip = branch_tip(&repo, &wip_branch_name);
+        commit_wip(&repo, &branch_ref, &tip, "wip: new change", 10);
+
+        let mut args = Args::parse_from(["gwipt"]);
+        args.retain_max_age = Some(500.0);
+        run_retain(&repo, &args, &wip_branch_name);
+
+        let tip = branch_tip(&repo, &wip_branch_name);
+        let chain = first_parent_chain(&tip);
+        assert_eq!(chain[0], "wip: new change");
+        assert_eq!(chain[1], "wip: roll up 1 commits");
+        assert_eq!(chain[2], "baseline");
+    }
+}